@@ -1,22 +1,31 @@
-use std::ascii::AsciiExt;
-use std::fmt;
-use std::io::prelude::*;
-use std::io;
-use std::io::{Cursor, ErrorKind, SeekFrom};
-use std::str;
-use byteorder::{LittleEndian, ReadBytesExt};
-use chrono::{DateTime, Date, TimeZone, Local};
+use core::fmt;
 
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use byteorder::{ByteOrder, LittleEndian};
+use chrono::{DateTime, Date, FixedOffset, TimeZone};
+
+use error::Error;
 use fs::{FatSharedStateRef, FatSlice};
 use file::FatFile;
+use io::{IoBase, Read, ReadLeExt, ReadWriteSeek, Seek, SeekFrom, Write, WriteLeExt};
+use oem_cp::OemCpConverter;
+use table;
+use time::RawDateTime;
+
+pub(crate) enum FatDirReader<'b, IO: ReadWriteSeek + 'b> {
+    File(FatFile<'b, IO>),
+    Root(FatSlice<'b, IO>),
+}
 
-pub(crate) enum FatDirReader<'a, 'b: 'a> {
-    File(FatFile<'a, 'b>),
-    Root(FatSlice<'a, 'b>),
+impl <'b, IO: ReadWriteSeek> IoBase for FatDirReader<'b, IO> {
+    type Error = Error<IO::Error>;
 }
 
-impl <'a, 'b> Read for FatDirReader<'a, 'b> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+impl <'b, IO: ReadWriteSeek> Read for FatDirReader<'b, IO> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error<IO::Error>> {
         match self {
             &mut FatDirReader::File(ref mut file) => file.read(buf),
             &mut FatDirReader::Root(ref mut raw) => raw.read(buf),
@@ -24,8 +33,24 @@ impl <'a, 'b> Read for FatDirReader<'a, 'b> {
     }
 }
 
-impl <'a, 'b> Seek for FatDirReader<'a, 'b> {
-    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+impl <'b, IO: ReadWriteSeek> Write for FatDirReader<'b, IO> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error<IO::Error>> {
+        match self {
+            &mut FatDirReader::File(ref mut file) => file.write(buf),
+            &mut FatDirReader::Root(ref mut raw) => raw.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error<IO::Error>> {
+        match self {
+            &mut FatDirReader::File(ref mut file) => file.flush(),
+            &mut FatDirReader::Root(ref mut raw) => raw.flush(),
+        }
+    }
+}
+
+impl <'b, IO: ReadWriteSeek> Seek for FatDirReader<'b, IO> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error<IO::Error>> {
         match self {
             &mut FatDirReader::File(ref mut file) => file.seek(pos),
             &mut FatDirReader::Root(ref mut raw) => raw.seek(pos),
@@ -33,6 +58,17 @@ impl <'a, 'b> Seek for FatDirReader<'a, 'b> {
     }
 }
 
+impl <'b, IO: ReadWriteSeek> FatDirReader<'b, IO> {
+    // Resolves a position within this directory's logical stream to an absolute offset on the
+    // backing storage.
+    fn absolute_offset_of(&self, logical_pos: u64) -> Result<u64, Error<IO::Error>> {
+        match self {
+            &FatDirReader::File(ref file) => file.absolute_offset_of(logical_pos),
+            &FatDirReader::Root(ref raw) => Ok(raw.begin() + logical_pos),
+        }
+    }
+}
+
 
 
 bitflags! {
@@ -51,7 +87,7 @@ bitflags! {
 
 #[allow(dead_code)]
 #[derive(Clone, Debug, Default)]
-struct FatDirFileEntryData {
+pub(crate) struct FatDirFileEntryData {
     name: [u8; 11],
     attrs: FatFileAttributes,
     reserved_0: u8,
@@ -66,6 +102,17 @@ struct FatDirFileEntryData {
     size: u32,
 }
 
+impl FatDirFileEntryData {
+    fn first_cluster(&self) -> u32 {
+        ((self.first_cluster_hi as u32) << 16) | self.first_cluster_lo as u32
+    }
+
+    fn set_first_cluster(&mut self, cluster: u32) {
+        self.first_cluster_hi = (cluster >> 16) as u16;
+        self.first_cluster_lo = (cluster & 0xFFFF) as u16;
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Clone, Debug, Default)]
 struct FatDirLfnEntryData {
@@ -85,20 +132,110 @@ enum FatDirEntryData {
     Lfn(FatDirLfnEntryData),
 }
 
+/// Tracks a `FatDirFileEntryData` together with the byte offset of its 32-byte slot within the
+/// owning directory's stream, and flushes any fields that have since been changed in memory back
+/// to storage.
+///
+/// The offset is resolved to an absolute position on the backing storage rather than a position
+/// within some particular `FatDirReader`, since a `FatFile`'s directory entry needs to stay
+/// reachable for the lifetime of the open file even after the `FatDir` iterator that produced it
+/// is long gone.
+pub(crate) struct DirEntryEditor {
+    data: FatDirFileEntryData,
+    abs_pos: u64,
+    dirty: bool,
+}
+
+impl DirEntryEditor {
+    fn new(data: FatDirFileEntryData, abs_pos: u64) -> Self {
+        DirEntryEditor { data, abs_pos, dirty: false }
+    }
+
+    pub(crate) fn set_first_cluster(&mut self, cluster: u32) {
+        if self.data.first_cluster() != cluster {
+            self.data.set_first_cluster(cluster);
+            self.dirty = true;
+        }
+    }
+
+    pub(crate) fn set_size(&mut self, size: u32) {
+        if self.data.size != size {
+            self.data.size = size;
+            self.dirty = true;
+        }
+    }
+
+    pub(crate) fn flush<'b, IO: ReadWriteSeek>(&mut self, state: FatSharedStateRef<'b, IO>) -> Result<(), Error<IO::Error>> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut state = state.borrow_mut();
+        state.rdr.seek(SeekFrom::Start(self.abs_pos))?;
+        write_file_entry_data(&mut state.rdr, &self.data)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
-pub struct FatDirEntry<'a, 'b: 'a> {
+pub struct FatDirEntry<'b, IO: ReadWriteSeek + 'b> {
     data: FatDirFileEntryData,
     lfn: Vec<u16>,
-    state: FatSharedStateRef<'a, 'b>,
+    // Offset of this entry's 32-byte slot within the owning directory's logical stream. Only
+    // meaningful relative to the `FatDirReader` that produced it (see `abs_pos` for a position
+    // that remains valid once that reader is gone).
+    pos: u64,
+    // Offset of the first (highest-order) LFN entry belonging to this entry, if any.
+    lfn_pos: Option<u64>,
+    // Absolute byte offset of this entry's 32-byte slot on the backing storage.
+    abs_pos: u64,
+    // Whether this entry's short-name slot was marked deleted (first byte 0xE5). Only ever set
+    // when the entry came from `FatDir::list_raw`/an iterator with raw mode enabled - the normal
+    // iteration mode skips deleted entries entirely.
+    is_deleted: bool,
+    state: FatSharedStateRef<'b, IO>,
 }
 
-impl <'a, 'b> FatDirEntry<'a, 'b> {
+// Decodes a raw 11-byte 8.3 short name through the filesystem's `OemCpConverter`, trimming the
+// space-padding the on-disk format uses. Shared by `FatDirEntry::short_file_name` and short-name
+// generation, which needs the same decoding to compare a candidate short name back against the
+// long name it was derived from.
+fn decode_short_name<'b, IO: ReadWriteSeek>(state: FatSharedStateRef<'b, IO>, raw: &[u8; 11]) -> String {
+    let converter = &state.borrow().oem_cp_converter;
+    let decode = |bytes: &[u8]| -> String {
+        let end = bytes.iter().rposition(|&b| b != b' ').map(|i| i + 1).unwrap_or(0);
+        bytes[..end].iter().map(|&b| converter.decode(b)).collect()
+    };
+    let name = decode(&raw[0..8]);
+    let ext = decode(&raw[8..11]);
+    if ext.is_empty() { name } else { format!("{}.{}", name, ext) }
+}
+
+impl <'b, IO: ReadWriteSeek> FatDirEntry<'b, IO> {
+    /// Whether this entry's short-name slot is marked deleted (first byte 0xE5). Only ever `true`
+    /// for entries surfaced by `FatDir::list_raw`, since normal iteration skips them.
+    pub fn is_deleted(&self) -> bool {
+        self.is_deleted
+    }
+
+    /// The short 8.3 name, decoded through the filesystem's `OemCpConverter`.
+    ///
+    /// For a deleted entry (see `is_deleted`), the stored first byte (0xE5) has overwritten the
+    /// real one, which is unrecoverable; it's reconstructed here as `?`, the same placeholder
+    /// long-standing undelete tools use.
     pub fn short_file_name(&self) -> String {
-        let name = str::from_utf8(&self.data.name[0..8]).unwrap().trim_right();
-        let ext = str::from_utf8(&self.data.name[8..11]).unwrap().trim_right();
-        if ext == "" { name.to_string() } else { format!("{}.{}", name, ext) }
+        // Byte 0 of the raw name doubles as the deleted-entry marker (0xE5), so a name that
+        // genuinely starts with 0xE5 stores 0x05 there instead; undo that before decoding. A
+        // truly deleted entry's first byte is gone for good, so substitute a placeholder.
+        let mut raw = self.data.name;
+        if self.is_deleted {
+            raw[0] = b'?';
+        } else if raw[0] == 0x05 {
+            raw[0] = 0xE5;
+        }
+        decode_short_name(self.state, &raw)
     }
-    
+
     pub fn file_name(&self) -> String {
         if self.lfn.len() > 0 {
             String::from_utf16(&self.lfn).unwrap()
@@ -106,92 +243,185 @@ impl <'a, 'b> FatDirEntry<'a, 'b> {
             self.short_file_name()
         }
     }
-    
+
     pub fn attributes(&self) -> FatFileAttributes {
         self.data.attrs
     }
-    
+
     pub fn is_dir(&self) -> bool {
         self.data.attrs.contains(FatFileAttributes::DIRECTORY)
     }
-    
+
     pub fn is_file(&self) -> bool {
         !self.is_dir()
     }
-    
-    pub(crate) fn first_cluster(&self) -> u32 {
-        ((self.data.first_cluster_hi as u32) << 16) | self.data.first_cluster_lo as u32
+
+    /// The first cluster of this entry's contents, as stored on disk - 0 for an empty file.
+    ///
+    /// Exposed so a caller recovering a deleted entry (see `is_deleted`) can walk its cluster
+    /// chain directly, since the FAT links themselves may already be freed or reused.
+    pub fn first_cluster(&self) -> u32 {
+        self.data.first_cluster()
     }
-    
-    pub fn to_file(&self) -> FatFile<'a, 'b> {
+
+    pub fn to_file(&self) -> FatFile<'b, IO> {
         if self.is_dir() {
             panic!("This is a directory");
         }
-        FatFile::new(self.first_cluster(), Some(self.data.size), self.state)
+        let editor = DirEntryEditor::new(self.data.clone(), self.abs_pos);
+        FatFile::with_editor(self.first_cluster(), Some(self.data.size as u64), self.state, Some(editor))
     }
-    
-    pub fn to_dir(&self) -> FatDir<'a, 'b> {
+
+    pub fn to_dir(&self) -> FatDir<'b, IO> {
         if !self.is_dir() {
             panic!("This is a file");
         }
         let file = FatFile::new(self.first_cluster(), None, self.state);
         FatDir::new(FatDirReader::File(file), self.state)
     }
-    
+
     pub fn len(&self) -> u64 {
         self.data.size as u64
     }
-    
-    pub fn created(&self) -> DateTime<Local> {
-        Self::convert_date_time(self.data.create_date, self.data.create_time_1)
+
+    /// When this entry was created, in the timezone of the filesystem's `TimeProvider`.
+    pub fn created(&self) -> DateTime<FixedOffset> {
+        self.apply_offset(Self::decode_date_time(self.data.create_date, self.data.create_time_1))
+    }
+
+    /// When this entry was last accessed, in the timezone of the filesystem's `TimeProvider`.
+    /// FAT only stores a date (no time of day) for this field.
+    pub fn accessed(&self) -> Date<FixedOffset> {
+        let (year, month, day) = Self::decode_date(self.data.access_date);
+        self.state.borrow().time_provider.utc_offset().ymd(year as i32, month as u32, day as u32)
+    }
+
+    /// When this entry was last modified, in the timezone of the filesystem's `TimeProvider`.
+    pub fn modified(&self) -> DateTime<FixedOffset> {
+        self.apply_offset(Self::decode_date_time(self.data.modify_date, self.data.modify_time))
+    }
+
+    /// The raw components of `created()`, decoded with no timezone attached.
+    pub fn created_raw(&self) -> RawDateTime {
+        Self::decode_date_time(self.data.create_date, self.data.create_time_1)
+    }
+
+    /// The raw `(year, month, day)` of `accessed()`, decoded with no timezone attached.
+    pub fn accessed_raw(&self) -> (u16, u8, u8) {
+        Self::decode_date(self.data.access_date)
     }
-    
-    pub fn accessed(&self) -> Date<Local> {
-        Self::convert_date(self.data.access_date)
+
+    /// The raw components of `modified()`, decoded with no timezone attached.
+    pub fn modified_raw(&self) -> RawDateTime {
+        Self::decode_date_time(self.data.modify_date, self.data.modify_time)
     }
-    
-    pub fn modified(&self) -> DateTime<Local> {
-        Self::convert_date_time(self.data.modify_date, self.data.modify_time)
+
+    fn apply_offset(&self, raw: RawDateTime) -> DateTime<FixedOffset> {
+        self.state.borrow().time_provider.utc_offset()
+            .ymd(raw.year as i32, raw.month as u32, raw.day as u32)
+            .and_hms(raw.hour as u32, raw.min as u32, raw.sec as u32)
     }
-    
-    fn convert_date(dos_date: u16) -> Date<Local> {
+
+    fn decode_date(dos_date: u16) -> (u16, u8, u8) {
         let (year, month, day) = ((dos_date >> 9) + 1980, (dos_date >> 5) & 0xF, dos_date & 0x1F);
-        Local.ymd(year as i32, month as u32, day as u32)
+        (year, month as u8, day as u8)
     }
-    
-    fn convert_date_time(dos_date: u16, dos_time: u16) -> DateTime<Local> {
+
+    fn decode_date_time(dos_date: u16, dos_time: u16) -> RawDateTime {
+        let (year, month, day) = Self::decode_date(dos_date);
         let (hour, min, sec) = (dos_time >> 11, (dos_time >> 5) & 0x3F, (dos_time & 0x1F) * 2);
-        FatDirEntry::convert_date(dos_date).and_hms(hour as u32, min as u32, sec as u32)
+        RawDateTime { year, month, day, hour: hour as u8, min: min as u8, sec: sec as u8 }
+    }
+
+    fn encode_date_time(dt: &RawDateTime) -> (u16, u16) {
+        let date = (((dt.year - 1980) as u16) << 9) | ((dt.month as u16) << 5) | dt.day as u16;
+        let time = ((dt.hour as u16) << 11) | ((dt.min as u16) << 5) | (dt.sec as u16 / 2);
+        (date, time)
     }
 }
 
-impl <'a, 'b> fmt::Debug for FatDirEntry<'a, 'b> {
+impl <'b, IO: ReadWriteSeek> fmt::Debug for FatDirEntry<'b, IO> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         self.data.fmt(f)
     }
 }
 
-pub struct FatDir<'a, 'b: 'a> {
-    rdr: FatDirReader<'a, 'b>,
-    state: FatSharedStateRef<'a, 'b>,
+// Writes the 32-byte on-disk representation of `data` to `w`, in the same field order
+// `read_dir_entry_data` reads them back in. Generic over any `Write` backend (a directory
+// stream, the root directory slice, or the raw storage behind a `DirEntryEditor`) rather than
+// tied to a particular `FatDir`'s storage type.
+fn write_file_entry_data<W: Write>(w: &mut W, data: &FatDirFileEntryData) -> Result<(), W::Error> {
+    w.write_all(&data.name)?;
+    w.write_u8(data.attrs.bits())?;
+    w.write_u8(data.reserved_0)?;
+    w.write_u8(data.create_time_0)?;
+    w.write_u16_le(data.create_time_1)?;
+    w.write_u16_le(data.create_date)?;
+    w.write_u16_le(data.access_date)?;
+    w.write_u16_le(data.first_cluster_hi)?;
+    w.write_u16_le(data.modify_time)?;
+    w.write_u16_le(data.modify_date)?;
+    w.write_u16_le(data.first_cluster_lo)?;
+    w.write_u32_le(data.size)?;
+    Ok(())
+}
+
+fn write_lfn_entry_data<W: Write>(w: &mut W, data: &FatDirLfnEntryData) -> Result<(), W::Error> {
+    w.write_u8(data.order)?;
+    for c in data.name_0.iter() {
+        w.write_u16_le(*c)?;
+    }
+    w.write_u8(data.attrs.bits())?;
+    w.write_u8(data.entry_type)?;
+    w.write_u8(data.checksum)?;
+    for c in data.name_1.iter() {
+        w.write_u16_le(*c)?;
+    }
+    w.write_u16_le(data.reserved_0)?;
+    for c in data.name_2.iter() {
+        w.write_u16_le(*c)?;
+    }
+    Ok(())
+}
+
+pub struct FatDir<'b, IO: ReadWriteSeek + 'b> {
+    rdr: FatDirReader<'b, IO>,
+    state: FatSharedStateRef<'b, IO>,
+    // When set, iteration also yields entries whose short-name slot is marked deleted (0xE5),
+    // instead of silently skipping them. Only ever toggled for the duration of `list_raw`.
+    include_deleted: bool,
 }
 
-impl <'a, 'b> FatDir<'a, 'b> {
-    
-    pub(crate) fn new(rdr: FatDirReader<'a, 'b>, state: FatSharedStateRef<'a, 'b>) -> FatDir<'a, 'b> {
-        FatDir { rdr, state }
+impl <'b, IO: ReadWriteSeek> FatDir<'b, IO> {
+
+    pub(crate) fn new(rdr: FatDirReader<'b, IO>, state: FatSharedStateRef<'b, IO>) -> FatDir<'b, IO> {
+        FatDir { rdr, state, include_deleted: false }
     }
-    
-    pub fn list(&mut self) -> io::Result<Vec<FatDirEntry<'a, 'b>>> {
+
+    pub fn list(&mut self) -> Result<Vec<FatDirEntry<'b, IO>>, Error<IO::Error>> {
         self.rewind();
         Ok(self.map(|x| x.unwrap()).collect())
     }
-    
+
+    /// Like `list`, but also yields entries the normal iteration skips as deleted (first
+    /// short-name byte 0xE5) - useful for forensic inspection of old disk images. A deleted
+    /// entry's long file name is never trusted (the fragments preceding it are erased the same
+    /// way), but its short name (see `FatDirEntry::short_file_name`), first cluster and size are
+    /// still returned as stored, so a caller can attempt to recover its contents by reading the
+    /// cluster chain even though the FAT links themselves may already be freed or reused.
+    pub fn list_raw(&mut self) -> Result<Vec<FatDirEntry<'b, IO>>, Error<IO::Error>> {
+        self.rewind();
+        self.include_deleted = true;
+        let result = self.map(|x| x.unwrap()).collect();
+        self.include_deleted = false;
+        Ok(result)
+    }
+
     pub fn rewind(&mut self) {
         self.rdr.seek(SeekFrom::Start(0)).unwrap();
     }
-    
-    fn read_dir_entry_data(&mut self) -> io::Result<FatDirEntryData> {
+
+    fn read_dir_entry_data(&mut self) -> Result<FatDirEntryData, Error<IO::Error>> {
         let mut name = [0; 11];
         self.rdr.read(&mut name)?;
         let attrs = FatFileAttributes::from_bits(self.rdr.read_u8()?).expect("invalid attributes");
@@ -199,14 +429,15 @@ impl <'a, 'b> FatDir<'a, 'b> {
             let mut data = FatDirLfnEntryData {
                 attrs, ..Default::default()
             };
-            let mut cur = Cursor::new(&name);
-            data.order = cur.read_u8()?;
-            cur.read_u16_into::<LittleEndian>(&mut data.name_0)?;
+            // The first 11 bytes overlap with the short-name field for a regular entry; pull the
+            // order byte and first name fragment back out of the buffer we already read.
+            data.order = name[0];
+            LittleEndian::read_u16_into(&name[1..11], &mut data.name_0);
             data.entry_type = self.rdr.read_u8()?;
             data.checksum = self.rdr.read_u8()?;
-            self.rdr.read_u16_into::<LittleEndian>(&mut data.name_1)?;
-            data.reserved_0 = self.rdr.read_u16::<LittleEndian>()?;
-            self.rdr.read_u16_into::<LittleEndian>(&mut data.name_2)?;
+            self.rdr.read_u16_into_le(&mut data.name_1)?;
+            data.reserved_0 = self.rdr.read_u16_le()?;
+            self.rdr.read_u16_into_le(&mut data.name_2)?;
             Ok(FatDirEntryData::Lfn(data))
         } else {
             let data = FatDirFileEntryData {
@@ -214,37 +445,37 @@ impl <'a, 'b> FatDir<'a, 'b> {
                 attrs,
                 reserved_0:       self.rdr.read_u8()?,
                 create_time_0:    self.rdr.read_u8()?,
-                create_time_1:    self.rdr.read_u16::<LittleEndian>()?,
-                create_date:      self.rdr.read_u16::<LittleEndian>()?,
-                access_date:      self.rdr.read_u16::<LittleEndian>()?,
-                first_cluster_hi: self.rdr.read_u16::<LittleEndian>()?,
-                modify_time:      self.rdr.read_u16::<LittleEndian>()?,
-                modify_date:      self.rdr.read_u16::<LittleEndian>()?,
-                first_cluster_lo: self.rdr.read_u16::<LittleEndian>()?,
-                size:             self.rdr.read_u32::<LittleEndian>()?,
+                create_time_1:    self.rdr.read_u16_le()?,
+                create_date:      self.rdr.read_u16_le()?,
+                access_date:      self.rdr.read_u16_le()?,
+                first_cluster_hi: self.rdr.read_u16_le()?,
+                modify_time:      self.rdr.read_u16_le()?,
+                modify_date:      self.rdr.read_u16_le()?,
+                first_cluster_lo: self.rdr.read_u16_le()?,
+                size:             self.rdr.read_u32_le()?,
             };
             Ok(FatDirEntryData::File(data))
         }
     }
-    
+
     fn split_path<'c>(path: &'c str) -> (&'c str, Option<&'c str>) {
         let mut path_split = path.trim_matches('/').splitn(2, "/");
         let comp = path_split.next().unwrap();
         let rest_opt = path_split.next();
         (comp, rest_opt)
     }
-    
-    fn find_entry(&mut self, name: &str) -> io::Result<FatDirEntry<'a, 'b>> {
-        let entries: Vec<FatDirEntry<'a, 'b>> = self.list()?;
+
+    fn find_entry(&mut self, name: &str) -> Result<FatDirEntry<'b, IO>, Error<IO::Error>> {
+        let entries: Vec<FatDirEntry<'b, IO>> = self.list()?;
         for e in entries {
             if e.file_name().eq_ignore_ascii_case(name) {
                 return Ok(e);
             }
         }
-        Err(io::Error::new(ErrorKind::NotFound, "file not found"))
+        Err(Error::NotFound)
     }
-    
-    pub fn open_dir(&mut self, path: &str) -> io::Result<FatDir<'a, 'b>> {
+
+    pub fn open_dir(&mut self, path: &str) -> Result<FatDir<'b, IO>, Error<IO::Error>> {
         let (name, rest_opt) = Self::split_path(path);
         let e = self.find_entry(name)?;
         match rest_opt {
@@ -252,8 +483,8 @@ impl <'a, 'b> FatDir<'a, 'b> {
             None => Ok(e.to_dir())
         }
     }
-    
-    pub fn open_file(&mut self, path: &str) -> io::Result<FatFile<'a, 'b>> {
+
+    pub fn open_file(&mut self, path: &str) -> Result<FatFile<'b, IO>, Error<IO::Error>> {
         let (name, rest_opt) = Self::split_path(path);
         let e = self.find_entry(name)?;
         match rest_opt {
@@ -261,14 +492,396 @@ impl <'a, 'b> FatDir<'a, 'b> {
             None => Ok(e.to_file())
         }
     }
+
+    /// The first cluster of this directory's own stream, or 0 for the (fixed-size) root
+    /// directory, matching the convention used for a ".." entry's first cluster field.
+    pub(crate) fn first_cluster(&self) -> u32 {
+        match self.rdr {
+            FatDirReader::File(ref file) => file.first_cluster(),
+            FatDirReader::Root(_) => 0,
+        }
+    }
+
+    // Scans the directory stream for `num_entries` contiguous free (deleted or never-used) 32-byte
+    // slots, growing the stream by one cluster if none are found. Returns the offset, within this
+    // directory's own `FatDirReader`, of the first free slot.
+    fn find_free_entries(&mut self, num_entries: usize) -> Result<u64, Error<IO::Error>> {
+        self.rewind();
+        let mut free_start: Option<u64> = None;
+        let mut free_count = 0usize;
+        let end_pos = loop {
+            let pos = self.rdr.seek(SeekFrom::Current(0))?;
+            let mut first_byte = [0u8; 1];
+            let n = self.rdr.read(&mut first_byte)?;
+            if n == 0 {
+                break pos;
+            }
+            self.rdr.seek(SeekFrom::Current(31))?;
+            if first_byte[0] == 0x00 || first_byte[0] == 0xE5 {
+                if free_start.is_none() {
+                    free_start = Some(pos);
+                }
+                free_count += 1;
+                if free_count >= num_entries {
+                    return Ok(free_start.unwrap());
+                }
+            } else {
+                free_start = None;
+                free_count = 0;
+            }
+        };
+        match self.rdr {
+            FatDirReader::Root(_) => Err(Error::NotEnoughSpace),
+            FatDirReader::File(_) => {
+                let remaining = num_entries - free_count;
+                let zeros = vec![0u8; remaining * 32];
+                self.rdr.seek(SeekFrom::Start(end_pos))?;
+                self.rdr.write_all(&zeros)?;
+                Ok(free_start.unwrap_or(end_pos))
+            },
+        }
+    }
+
+    // Writes `lfn_entries` (already in on-disk order, highest order first) followed by `data`
+    // into a freshly found (or allocated) run of free directory slots. Returns the offset of
+    // `data`'s own slot so a `DirEntryEditor` can be built for it later.
+    fn write_new_entry(&mut self, lfn_entries: &[FatDirLfnEntryData], data: &FatDirFileEntryData) -> Result<u64, Error<IO::Error>> {
+        let num_entries = lfn_entries.len() + 1;
+        let start = self.find_free_entries(num_entries)?;
+        self.rdr.seek(SeekFrom::Start(start))?;
+        for lfn in lfn_entries {
+            write_lfn_entry_data(&mut self.rdr, lfn)?;
+        }
+        let file_pos = self.rdr.seek(SeekFrom::Current(0))?;
+        write_file_entry_data(&mut self.rdr, data)?;
+        Ok(file_pos)
+    }
+
+    // Marks `entry`'s short-name slot, and every LFN slot preceding it, as deleted (0xE5). Does
+    // not free the entry's cluster chain - callers that are actually deleting the entry (as
+    // opposed to moving it, see `rename`) do that separately.
+    fn mark_entry_deleted(&mut self, entry: &FatDirEntry<'b, IO>) -> Result<(), Error<IO::Error>> {
+        let start = entry.lfn_pos.unwrap_or(entry.pos);
+        let mut pos = start;
+        while pos <= entry.pos {
+            self.rdr.seek(SeekFrom::Start(pos))?;
+            self.rdr.write_u8(0xE5)?;
+            pos += 32;
+        }
+        Ok(())
+    }
+
+    // Builds the short 8.3 name for `name`, picking a `~N` numeric tail if the name doesn't fit
+    // in 8.3 or collides with an entry already present.
+    fn generate_short_name(&mut self, name: &str) -> Result<[u8; 11], Error<IO::Error>> {
+        let upper = name.to_ascii_uppercase();
+        let (base, ext) = match upper.rfind('.') {
+            Some(i) => (&upper[..i], &upper[i+1..]),
+            None => (&upper[..], ""),
+        };
+        let base: String = base.chars().filter(|c| *c != ' ').collect();
+        let ext: String = ext.chars().filter(|c| *c != ' ').collect();
+
+        // Short names are stored one byte per character in the volume's OEM code page, not UTF-8,
+        // so every character has to round-trip through `OemCpConverter::encode` rather than being
+        // byte-truncated out of a UTF-8 `str` - raw UTF-8 bytes would decode back (see
+        // `decode_short_name`) as unrelated code-page characters.
+        let base_encoded = Self::encode_short_name_component(self.state, &base);
+        let ext_encoded = Self::encode_short_name_component(self.state, &ext);
+        let fits_8_3 = !base.is_empty() && base.chars().count() <= 8 && ext.chars().count() <= 3
+            && upper.matches('.').count() <= 1
+            && base_encoded.is_some() && ext_encoded.is_some();
+
+        let existing = self.list()?;
+        let make_name = |base: &[u8], ext: &[u8]| -> [u8; 11] {
+            let mut raw = [b' '; 11];
+            for (i, &b) in base.iter().take(8).enumerate() {
+                raw[i] = b;
+            }
+            for (i, &b) in ext.iter().take(3).enumerate() {
+                raw[8 + i] = b;
+            }
+            raw
+        };
+        let collides = |raw: &[u8; 11]| existing.iter().any(|e| e.data.name == *raw);
+
+        if fits_8_3 {
+            let raw = make_name(&base_encoded.unwrap(), &ext_encoded.unwrap());
+            if !collides(&raw) {
+                return Ok(raw);
+            }
+        }
+
+        // Numbered-suffix fallback: a character the code page can't represent becomes `_`,
+        // matching how DOS/Windows derive a short name from a long name that doesn't fit as-is.
+        let base_bytes = Self::encode_short_name_component_lossy(self.state, &base);
+        let ext_bytes = Self::encode_short_name_component_lossy(self.state, &ext);
+        for n in 1u32..=999999 {
+            let suffix = format!("~{}", n);
+            let trimmed_len = 8usize.saturating_sub(suffix.len());
+            let mut trimmed_base: Vec<u8> = base_bytes.iter().take(trimmed_len).cloned().collect();
+            trimmed_base.extend(suffix.bytes());
+            let raw = make_name(&trimmed_base, &ext_bytes);
+            if !collides(&raw) {
+                return Ok(raw);
+            }
+        }
+        Err(Error::InvalidFileNameLength)
+    }
+
+    // Encodes `s` into the volume's OEM code page, one byte per character, failing if any
+    // character is unrepresentable (or is a space/period, which the short-name format can't hold
+    // here - those are handled separately by the caller).
+    fn encode_short_name_component(state: FatSharedStateRef<'b, IO>, s: &str) -> Option<Vec<u8>> {
+        let converter = &state.borrow().oem_cp_converter;
+        s.chars().map(|c| {
+            if c as u32 <= 0x20 || c == '.' { None } else { converter.encode(c) }
+        }).collect()
+    }
+
+    // Same encoding, but infallible: a character the code page can't represent becomes `_`.
+    fn encode_short_name_component_lossy(state: FatSharedStateRef<'b, IO>, s: &str) -> Vec<u8> {
+        let converter = &state.borrow().oem_cp_converter;
+        s.chars().map(|c| converter.encode(c).unwrap_or(b'_')).collect()
+    }
+
+    // Computes the 8-bit LFN checksum of an 8.3 short name, per the FAT specification: every LFN
+    // entry in a run stores this so a reader can tell it belongs to the short entry that follows
+    // rather than some unrelated (or orphaned) one.
+    fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+        let mut sum: u8 = 0;
+        for &b in short_name.iter() {
+            sum = ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(b);
+        }
+        sum
+    }
+
+    // Builds the chain of 0x0F-attribute LFN entries needed to recover `name`, in on-disk order
+    // (the entry with the 0x40 "last logical entry" flag and the highest order number first).
+    // Empty if `short_as_name` matches `name` ignoring ASCII case, so a name that differs from its
+    // short name only in case gets no LFN entries and is listed in upper case.
+    fn build_lfn_entries(name: &str, short_as_name: &str, short_name: &[u8; 11]) -> Vec<FatDirLfnEntryData> {
+        if short_as_name.eq_ignore_ascii_case(name) {
+            return Vec::new();
+        }
+
+        let checksum = Self::lfn_checksum(short_name);
+        let name_utf16: Vec<u16> = name.encode_utf16().collect();
+        const LFN_PART_LEN: usize = 13;
+        let num_entries = (name_utf16.len() + LFN_PART_LEN - 1) / LFN_PART_LEN.max(1);
+        let num_entries = num_entries.max(1);
+        let mut entries = Vec::with_capacity(num_entries);
+        for i in 0..num_entries {
+            let start = i * LFN_PART_LEN;
+            let mut part = [0xFFFFu16; LFN_PART_LEN];
+            for j in 0..LFN_PART_LEN {
+                if start + j < name_utf16.len() {
+                    part[j] = name_utf16[start + j];
+                } else if start + j == name_utf16.len() {
+                    part[j] = 0;
+                }
+            }
+            let mut data = FatDirLfnEntryData {
+                order: (i + 1) as u8,
+                attrs: FatFileAttributes::LFN,
+                checksum,
+                ..Default::default()
+            };
+            data.name_0.copy_from_slice(&part[0..5]);
+            data.name_1.copy_from_slice(&part[5..11]);
+            data.name_2.copy_from_slice(&part[11..13]);
+            if i == num_entries - 1 {
+                data.order |= 0x40;
+            }
+            entries.push(data);
+        }
+        // On-disk order is highest order (with the 0x40 flag) first.
+        entries.reverse();
+        entries
+    }
+
+    /// Creates a new, empty file named `name` in this directory and returns a handle to it.
+    ///
+    /// Fails with `Error::AlreadyExists` if an entry with that name is already present.
+    pub fn create_file(&mut self, name: &str) -> Result<FatFile<'b, IO>, Error<IO::Error>> {
+        if self.find_entry(name).is_ok() {
+            return Err(Error::AlreadyExists);
+        }
+        let short_name = self.generate_short_name(name)?;
+        let short_as_name = decode_short_name(self.state, &short_name);
+        let lfn_entries = Self::build_lfn_entries(name, &short_as_name, &short_name);
+        let now = self.state.borrow().time_provider.get_current_date_time();
+        let (date, time) = FatDirEntry::<IO>::encode_date_time(&now);
+        let data = FatDirFileEntryData {
+            name: short_name,
+            attrs: FatFileAttributes::ARCHIVE,
+            create_date: date,
+            create_time_1: time,
+            modify_date: date,
+            modify_time: time,
+            access_date: date,
+            ..Default::default()
+        };
+        let pos = self.write_new_entry(&lfn_entries, &data)?;
+        // `write_new_entry` returns a position within this directory's own logical stream, but
+        // `DirEntryEditor` needs an absolute storage offset so it stays valid once this `FatDir`
+        // is gone (see the iterator and `to_file`, which convert the same way).
+        let abs_pos = self.rdr.absolute_offset_of(pos)?;
+        let editor = DirEntryEditor::new(data, abs_pos);
+        Ok(FatFile::with_editor(0, Some(0), self.state, Some(editor)))
+    }
+
+    /// Creates a new, empty subdirectory named `name` (with "." and ".." entries already in
+    /// place) and returns a handle to it.
+    ///
+    /// Fails with `Error::AlreadyExists` if an entry with that name is already present.
+    pub fn create_dir(&mut self, name: &str) -> Result<FatDir<'b, IO>, Error<IO::Error>> {
+        if self.find_entry(name).is_ok() {
+            return Err(Error::AlreadyExists);
+        }
+        let short_name = self.generate_short_name(name)?;
+        let short_as_name = decode_short_name(self.state, &short_name);
+        let lfn_entries = Self::build_lfn_entries(name, &short_as_name, &short_name);
+        let now = self.state.borrow().time_provider.get_current_date_time();
+        let (date, time) = FatDirEntry::<IO>::encode_date_time(&now);
+        let new_cluster = table::alloc_cluster(self.state, None)?;
+        let mut data = FatDirFileEntryData {
+            name: short_name,
+            attrs: FatFileAttributes::DIRECTORY,
+            create_date: date,
+            create_time_1: time,
+            modify_date: date,
+            modify_time: time,
+            access_date: date,
+            ..Default::default()
+        };
+        data.set_first_cluster(new_cluster);
+        self.write_new_entry(&lfn_entries, &data)?;
+
+        let parent_cluster = self.first_cluster();
+        let mut new_dir_stream = FatFile::new(new_cluster, None, self.state);
+        let mut dot = FatDirFileEntryData {
+            name: *b".          ",
+            attrs: FatFileAttributes::DIRECTORY,
+            create_date: date,
+            create_time_1: time,
+            modify_date: date,
+            modify_time: time,
+            access_date: date,
+            ..Default::default()
+        };
+        dot.set_first_cluster(new_cluster);
+        let mut dotdot = FatDirFileEntryData {
+            name: *b"..         ",
+            attrs: FatFileAttributes::DIRECTORY,
+            create_date: date,
+            create_time_1: time,
+            modify_date: date,
+            modify_time: time,
+            access_date: date,
+            ..Default::default()
+        };
+        dotdot.set_first_cluster(parent_cluster);
+        write_file_entry_data(&mut new_dir_stream, &dot)?;
+        write_file_entry_data(&mut new_dir_stream, &dotdot)?;
+
+        let file = FatFile::new(new_cluster, None, self.state);
+        Ok(FatDir::new(FatDirReader::File(file), self.state))
+    }
+
+    /// Removes the file or empty directory named `name` from this directory, freeing its cluster
+    /// chain (if any) and marking its short entry (and any LFN entries) as deleted.
+    ///
+    /// Fails with `Error::DirectoryIsNotEmpty` if `name` refers to a non-empty directory.
+    pub fn remove(&mut self, name: &str) -> Result<(), Error<IO::Error>> {
+        let entry = self.find_entry(name)?;
+        if entry.is_dir() {
+            let mut subdir = entry.to_dir();
+            let has_children = subdir.list()?.into_iter().any(|e| {
+                let short = e.short_file_name();
+                short != "." && short != ".."
+            });
+            if has_children {
+                return Err(Error::DirectoryIsNotEmpty);
+            }
+        }
+        self.mark_entry_deleted(&entry)?;
+        if entry.first_cluster() != 0 {
+            table::free_cluster_chain(self.state, entry.first_cluster())?;
+        }
+        Ok(())
+    }
+
+    /// Moves the entry named `src_name` in this directory to `dst_name` in `dst_dir`, or within
+    /// this same directory if `dst_dir` is `None` (passing `self` as `dst_dir` isn't possible:
+    /// that would require borrowing `self` mutably twice at once). Preserves the entry's contents
+    /// and cluster chain, and fixes up a moved subdirectory's own `..` entry to point at the new
+    /// parent.
+    ///
+    /// Fails with `Error::AlreadyExists` if `dst_name` is already present in the destination.
+    pub fn rename(&mut self, src_name: &str, dst_dir: Option<&mut FatDir<'b, IO>>, dst_name: &str) -> Result<(), Error<IO::Error>> {
+        match dst_dir {
+            Some(dst_dir) => self.rename_to(src_name, dst_dir, dst_name),
+            None => self.rename_within(src_name, dst_name),
+        }
+    }
+
+    fn rename_within(&mut self, src_name: &str, dst_name: &str) -> Result<(), Error<IO::Error>> {
+        let entry = self.find_entry(src_name)?;
+        if !entry.file_name().eq_ignore_ascii_case(dst_name) && self.find_entry(dst_name).is_ok() {
+            return Err(Error::AlreadyExists);
+        }
+        let short_name = self.generate_short_name(dst_name)?;
+        let short_as_name = decode_short_name(self.state, &short_name);
+        let lfn_entries = Self::build_lfn_entries(dst_name, &short_as_name, &short_name);
+        let mut data = entry.data.clone();
+        data.name = short_name;
+        self.write_new_entry(&lfn_entries, &data)?;
+        self.mark_entry_deleted(&entry)?;
+        Ok(())
+    }
+
+    fn rename_to(&mut self, src_name: &str, dst_dir: &mut FatDir<'b, IO>, dst_name: &str) -> Result<(), Error<IO::Error>> {
+        let entry = self.find_entry(src_name)?;
+        if dst_dir.find_entry(dst_name).is_ok() {
+            return Err(Error::AlreadyExists);
+        }
+        let short_name = dst_dir.generate_short_name(dst_name)?;
+        let short_as_name = decode_short_name(dst_dir.state, &short_name);
+        let lfn_entries = Self::build_lfn_entries(dst_name, &short_as_name, &short_name);
+        let mut data = entry.data.clone();
+        data.name = short_name;
+        dst_dir.write_new_entry(&lfn_entries, &data)?;
+        self.mark_entry_deleted(&entry)?;
+        if entry.is_dir() {
+            // The moved subdirectory's own ".." entry still points at this directory's cluster;
+            // repoint it at the new parent now that it no longer lives here.
+            let mut moved_dir = entry.to_dir();
+            let dotdot = moved_dir.find_entry("..")?;
+            let mut editor = DirEntryEditor::new(dotdot.data.clone(), dotdot.abs_pos);
+            editor.set_first_cluster(dst_dir.first_cluster());
+            editor.flush(self.state)?;
+        }
+        Ok(())
+    }
 }
 
-impl <'a, 'b> Iterator for FatDir<'a, 'b> {
-    type Item = io::Result<FatDirEntry<'a, 'b>>;
+impl <'b, IO: ReadWriteSeek> Iterator for FatDir<'b, IO> {
+    type Item = Result<FatDirEntry<'b, IO>, Error<IO::Error>>;
 
-    fn next(&mut self) -> Option<io::Result<FatDirEntry<'a, 'b>>> {
+    fn next(&mut self) -> Option<Result<FatDirEntry<'b, IO>, Error<IO::Error>>> {
         let mut lfn_buf = Vec::<u16>::new();
+        let mut lfn_pos: Option<u64> = None;
+        // Running (order, checksum) of the last LFN fragment seen, used to check that the next
+        // one continues the same run: order must descend contiguously to 1, and every fragment
+        // must share the checksum of the short entry the run belongs to.
+        let mut lfn_state: Option<(u8, u8)> = None;
+        let mut lfn_intact = true;
         loop {
+            let entry_pos = match self.rdr.seek(SeekFrom::Current(0)) {
+                Ok(pos) => pos,
+                Err(err) => return Some(Err(err)),
+            };
             let res = self.read_dir_entry_data();
             let data = match res {
                 Ok(data) => data,
@@ -280,26 +893,52 @@ impl <'a, 'b> Iterator for FatDir<'a, 'b> {
                     if data.name[0] == 0 {
                         return None;
                     }
-                    // Check if this is deleted or volume ID entry
-                    if data.name[0] == 0xE5 || data.attrs.contains(FatFileAttributes::VOLUME_ID) {
+                    // Check if this is a deleted or volume ID entry. Deleted entries are only
+                    // skipped in normal iteration - `list_raw` surfaces them instead.
+                    let is_deleted = data.name[0] == 0xE5;
+                    if (is_deleted && !self.include_deleted) || data.attrs.contains(FatFileAttributes::VOLUME_ID) {
                         lfn_buf.clear();
+                        lfn_pos = None;
+                        lfn_state = None;
+                        lfn_intact = true;
                         continue;
                     }
-                    // Truncate 0 and 0xFFFF characters from LFN buffer
-                    let mut lfn_len = lfn_buf.len();
-                    loop {
-                        if lfn_len == 0 {
-                            break;
-                        }
-                        match lfn_buf[lfn_len-1] {
-                            0xFFFF | 0 => lfn_len -= 1,
-                            _ => break,
+                    // An LFN run is only trusted if every fragment descended contiguously to 1,
+                    // matched the short entry's checksum, and none were orphaned/corrupted along
+                    // the way - otherwise fall back to the short name rather than risk returning
+                    // a name stitched together from unrelated entries. A deleted entry's own LFN
+                    // fragments are erased the same way its short name is, so never trust one.
+                    let checksum_ok = lfn_state
+                        .map(|(order, checksum)| order == 1 && checksum == Self::lfn_checksum(&data.name))
+                        .unwrap_or(false);
+                    let (lfn_buf, lfn_pos) = if !is_deleted && lfn_intact && checksum_ok {
+                        // Truncate 0 and 0xFFFF characters from LFN buffer
+                        let mut lfn_len = lfn_buf.len();
+                        loop {
+                            if lfn_len == 0 {
+                                break;
+                            }
+                            match lfn_buf[lfn_len-1] {
+                                0xFFFF | 0 => lfn_len -= 1,
+                                _ => break,
+                            }
                         }
-                    }
-                    lfn_buf.truncate(lfn_len);
+                        lfn_buf.truncate(lfn_len);
+                        (lfn_buf, lfn_pos)
+                    } else {
+                        (Vec::new(), None)
+                    };
+                    let abs_pos = match self.rdr.absolute_offset_of(entry_pos) {
+                        Ok(pos) => pos,
+                        Err(err) => return Some(Err(err)),
+                    };
                     return Some(Ok(FatDirEntry {
                         data,
                         lfn: lfn_buf,
+                        pos: entry_pos,
+                        lfn_pos,
+                        abs_pos,
+                        is_deleted,
                         state: self.state.clone(),
                     }));
                 },
@@ -307,10 +946,28 @@ impl <'a, 'b> Iterator for FatDir<'a, 'b> {
                     // Check if this is deleted entry
                     if data.order == 0xE5 {
                         lfn_buf.clear();
+                        lfn_pos = None;
+                        lfn_state = None;
+                        lfn_intact = true;
                         continue;
                     }
+                    if lfn_pos.is_none() {
+                        lfn_pos = Some(entry_pos);
+                    }
+                    let order = data.order & 0x1F;
+                    let is_last = data.order & 0x40 != 0;
+                    let continues_run = match lfn_state {
+                        // The first fragment encountered (entries are stored highest-order
+                        // first) must be flagged as the last logical entry in the name.
+                        None => is_last,
+                        Some((prev_order, prev_checksum)) =>
+                            order + 1 == prev_order && data.checksum == prev_checksum,
+                    };
+                    lfn_intact = lfn_intact && continues_run;
+                    lfn_state = Some((order, data.checksum));
+
                     const LFN_PART_LEN: usize = 13;
-                    let index = (data.order & 0x1F) - 1;
+                    let index = order.saturating_sub(1);
                     let pos = LFN_PART_LEN * index as usize;
                     // resize LFN buffer to have enough space for entire name
                     if lfn_buf.len() < pos + LFN_PART_LEN {
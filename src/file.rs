@@ -0,0 +1,187 @@
+use core::cmp;
+
+use dir::DirEntryEditor;
+use error::Error;
+use fs::FatSharedStateRef;
+use io::{IoBase, Read, ReadWriteSeek, Seek, SeekFrom, Write};
+use table;
+
+/// A handle to an open file's cluster chain.
+///
+/// Tracks the current position as a byte offset from the start of the file; every read and write
+/// walks the cluster chain from the first cluster to find the cluster holding that offset.
+pub struct FatFile<'b, IO: ReadWriteSeek + 'b> {
+    first_cluster: u32,
+    current_cluster: Option<u32>,
+    // Byte offset from the start of the file.
+    offset: u64,
+    size: Option<u64>,
+    state: FatSharedStateRef<'b, IO>,
+    // Set for files opened through `FatDir::create_file`/`FatDirEntry::to_file`, so that growing the file
+    // updates the `size`/first-cluster fields of its directory entry. `None` for directory
+    // streams, which are addressed by cluster chain alone and have no size field to maintain.
+    editor: Option<DirEntryEditor>,
+}
+
+impl <'b, IO: ReadWriteSeek> FatFile<'b, IO> {
+    pub(crate) fn new(first_cluster: u32, size: Option<u64>, state: FatSharedStateRef<'b, IO>) -> Self {
+        Self::with_editor(first_cluster, size, state, None)
+    }
+
+    pub(crate) fn with_editor(
+        first_cluster: u32,
+        size: Option<u64>,
+        state: FatSharedStateRef<'b, IO>,
+        editor: Option<DirEntryEditor>,
+    ) -> Self {
+        FatFile {
+            first_cluster,
+            current_cluster: if first_cluster == 0 { None } else { Some(first_cluster) },
+            offset: 0,
+            size,
+            state,
+            editor,
+        }
+    }
+
+    pub(crate) fn first_cluster(&self) -> u32 {
+        self.first_cluster
+    }
+
+    fn bytes_per_cluster(&self) -> u64 {
+        self.state.borrow().bytes_per_cluster()
+    }
+
+    // Resolves a logical byte offset into this stream to an absolute offset on the backing
+    // storage, by walking the cluster chain. Used to give a `DirEntryEditor` a position that
+    // stays valid even after the directory stream that produced it goes away.
+    pub(crate) fn absolute_offset_of(&self, logical_pos: u64) -> Result<u64, Error<IO::Error>> {
+        let bytes_per_cluster = self.bytes_per_cluster();
+        let target_cluster_index = logical_pos / bytes_per_cluster;
+        let mut cluster = self.first_cluster;
+        for _ in 0..target_cluster_index {
+            match table::get_next_cluster(self.state, cluster)? {
+                Some(next) => cluster = next,
+                None => return Err(Error::UnexpectedEof),
+            }
+        }
+        let cluster_offset = logical_pos % bytes_per_cluster;
+        Ok(self.state.borrow().offset_from_cluster(cluster) + cluster_offset)
+    }
+
+    // Seeks the underlying storage to the byte that corresponds to `self.offset`, walking the
+    // cluster chain from `self.first_cluster` and caching the cluster it lands on in
+    // `current_cluster` (`None` if the chain ends before `self.offset`).
+    fn seek_to_offset(&mut self) -> Result<(), Error<IO::Error>> {
+        // An empty file (or one truncated back to 0) has no cluster chain to walk at all, not
+        // even a first one - `write` relies on `current_cluster` being `None` here to know it
+        // must allocate the file's first cluster rather than treat 0 as a real cluster number.
+        if self.first_cluster == 0 {
+            self.current_cluster = None;
+            return Ok(());
+        }
+        let bytes_per_cluster = self.bytes_per_cluster();
+        let target_cluster_index = self.offset / bytes_per_cluster;
+        let mut cluster = self.first_cluster;
+        for _ in 0..target_cluster_index {
+            match table::get_next_cluster(self.state, cluster)? {
+                Some(next) => cluster = next,
+                None => {
+                    self.current_cluster = None;
+                    return Ok(());
+                },
+            }
+        }
+        self.current_cluster = Some(cluster);
+        let cluster_offset = self.offset % bytes_per_cluster;
+        let byte_offset = self.state.borrow().offset_from_cluster(cluster) + cluster_offset;
+        self.state.borrow_mut().rdr.seek(SeekFrom::Start(byte_offset))?;
+        Ok(())
+    }
+}
+
+impl <'b, IO: ReadWriteSeek> IoBase for FatFile<'b, IO> {
+    type Error = Error<IO::Error>;
+}
+
+impl <'b, IO: ReadWriteSeek> Read for FatFile<'b, IO> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error<IO::Error>> {
+        if let Some(size) = self.size {
+            if self.offset >= size {
+                return Ok(0);
+            }
+        }
+        self.seek_to_offset()?;
+        let cluster = match self.current_cluster {
+            Some(c) => c,
+            None => return Ok(0),
+        };
+        let bytes_per_cluster = self.bytes_per_cluster();
+        let cluster_offset = self.offset % bytes_per_cluster;
+        let mut max_read = cmp::min(buf.len() as u64, bytes_per_cluster - cluster_offset);
+        if let Some(size) = self.size {
+            max_read = cmp::min(max_read, size - self.offset);
+        }
+        let n = self.state.borrow_mut().rdr.read(&mut buf[..max_read as usize])?;
+        self.offset += n as u64;
+        let _ = cluster;
+        Ok(n)
+    }
+}
+
+impl <'b, IO: ReadWriteSeek> Write for FatFile<'b, IO> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error<IO::Error>> {
+        self.seek_to_offset()?;
+        let bytes_per_cluster = self.bytes_per_cluster();
+        if self.current_cluster.is_none() {
+            // File is empty or we've run off the end of the chain - extend it.
+            let prev = if self.offset == 0 { None } else {
+                // Walk back to the last cluster in the chain.
+                let mut cluster = self.first_cluster;
+                loop {
+                    match table::get_next_cluster(self.state, cluster)? {
+                        Some(next) => cluster = next,
+                        None => break Some(cluster),
+                    }
+                }
+            };
+            let new_cluster = table::alloc_cluster(self.state, prev)?;
+            if self.first_cluster == 0 {
+                self.first_cluster = new_cluster;
+            }
+            self.current_cluster = Some(new_cluster);
+            let byte_offset = self.state.borrow().offset_from_cluster(new_cluster);
+            self.state.borrow_mut().rdr.seek(SeekFrom::Start(byte_offset))?;
+        }
+        let cluster_offset = self.offset % bytes_per_cluster;
+        let max_write = cmp::min(buf.len() as u64, bytes_per_cluster - cluster_offset) as usize;
+        let n = self.state.borrow_mut().rdr.write(&buf[..max_write])?;
+        self.offset += n as u64;
+        self.size = Some(cmp::max(self.size.unwrap_or(0), self.offset));
+        if let Some(ref mut editor) = self.editor {
+            editor.set_first_cluster(self.first_cluster);
+            editor.set_size(self.size.unwrap_or(0) as u32);
+            editor.flush(self.state)?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Error<IO::Error>> {
+        Ok(self.state.borrow_mut().rdr.flush()?)
+    }
+}
+
+impl <'b, IO: ReadWriteSeek> Seek for FatFile<'b, IO> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error<IO::Error>> {
+        let new_offset: i64 = match pos {
+            SeekFrom::Current(x) => self.offset as i64 + x,
+            SeekFrom::Start(x) => x as i64,
+            SeekFrom::End(x) => self.size.unwrap_or(0) as i64 + x,
+        };
+        if new_offset < 0 {
+            return Err(Error::InvalidInput);
+        }
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
@@ -0,0 +1,237 @@
+use core::cell::RefCell;
+use core::cmp;
+
+use alloc::boxed::Box;
+use byteorder::{ByteOrder, LittleEndian};
+
+use dir::{FatDir, FatDirReader};
+use error::Error;
+use io::{IntoStorage, IoBase, Read, ReadWriteSeek, Seek, SeekFrom, Write};
+use oem_cp::{Cp437OemCpConverter, OemCpConverter};
+use table::FatType;
+#[cfg(feature = "std")]
+use time::LocalTimeProvider;
+use time::TimeProvider;
+
+/// Mutable state shared by every `FatDir`/`FatFile`/`FatSlice` handle borrowed from a single
+/// `FatFileSystem`: the underlying storage plus the handful of BPB fields needed to translate
+/// between clusters, sectors and byte offsets.
+pub(crate) struct FatSharedState<IO: ReadWriteSeek> {
+    pub(crate) rdr: IO,
+    pub(crate) fat_type: FatType,
+    pub(crate) bytes_per_sector: u16,
+    pub(crate) sectors_per_cluster: u8,
+    pub(crate) reserved_sectors: u16,
+    pub(crate) fats: u8,
+    pub(crate) sectors_per_fat: u32,
+    pub(crate) root_dir_sectors: u32,
+    pub(crate) root_dir_first_sector: u32,
+    pub(crate) first_data_sector: u32,
+    pub(crate) total_clusters: u32,
+    pub(crate) oem_cp_converter: Box<dyn OemCpConverter>,
+    pub(crate) time_provider: Box<dyn TimeProvider>,
+}
+
+/// A shared, borrowed handle to the filesystem state. Every object handed out by the
+/// filesystem (`FatDir`, `FatFile`, `FatDirEntry`, ...) carries one of these so it can reach the
+/// backing storage and the FAT without owning it outright.
+pub type FatSharedStateRef<'b, IO> = &'b RefCell<FatSharedState<IO>>;
+
+impl <IO: ReadWriteSeek> FatSharedState<IO> {
+    pub(crate) fn bytes_per_sector(&self) -> u64 {
+        self.bytes_per_sector as u64
+    }
+
+    pub(crate) fn bytes_per_cluster(&self) -> u64 {
+        self.sectors_per_cluster as u64 * self.bytes_per_sector()
+    }
+
+    pub(crate) fn offset_from_sector(&self, sector: u32) -> u64 {
+        sector as u64 * self.bytes_per_sector()
+    }
+
+    pub(crate) fn first_sector_of_cluster(&self, cluster: u32) -> u32 {
+        self.first_data_sector + (cluster - 2) * self.sectors_per_cluster as u32
+    }
+
+    pub(crate) fn offset_from_cluster(&self, cluster: u32) -> u64 {
+        self.offset_from_sector(self.first_sector_of_cluster(cluster))
+    }
+
+    pub(crate) fn fat_offset(&self, fat_index: u8) -> u64 {
+        self.offset_from_sector(self.reserved_sectors as u32 + fat_index as u32 * self.sectors_per_fat)
+    }
+}
+
+/// A bounded, seekable view over a region of the underlying storage.
+///
+/// Used for the fixed-size FAT12/FAT16 root directory, which lives in its own reserved sectors
+/// rather than a cluster chain. See `partition::PartitionSlice` for the analogous view used to
+/// mount a single MBR partition's sectors as a volume.
+pub struct FatSlice<'b, IO: ReadWriteSeek + 'b> {
+    begin: u64,
+    size: u64,
+    offset: u64,
+    state: FatSharedStateRef<'b, IO>,
+}
+
+impl <'b, IO: ReadWriteSeek> FatSlice<'b, IO> {
+    pub(crate) fn new(begin: u64, size: u64, state: FatSharedStateRef<'b, IO>) -> Self {
+        FatSlice { begin, size, offset: 0, state }
+    }
+
+    pub(crate) fn begin(&self) -> u64 {
+        self.begin
+    }
+}
+
+impl <'b, IO: ReadWriteSeek> IoBase for FatSlice<'b, IO> {
+    type Error = Error<IO::Error>;
+}
+
+impl <'b, IO: ReadWriteSeek> Read for FatSlice<'b, IO> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error<IO::Error>> {
+        let remaining = self.size.saturating_sub(self.offset);
+        let max_read = cmp::min(buf.len() as u64, remaining) as usize;
+        if max_read == 0 {
+            return Ok(0);
+        }
+        let mut state = self.state.borrow_mut();
+        state.rdr.seek(SeekFrom::Start(self.begin + self.offset))?;
+        let n = state.rdr.read(&mut buf[..max_read])?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+impl <'b, IO: ReadWriteSeek> Write for FatSlice<'b, IO> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error<IO::Error>> {
+        let remaining = self.size.saturating_sub(self.offset);
+        let max_write = cmp::min(buf.len() as u64, remaining) as usize;
+        if max_write == 0 {
+            return Ok(0);
+        }
+        let mut state = self.state.borrow_mut();
+        state.rdr.seek(SeekFrom::Start(self.begin + self.offset))?;
+        let n = state.rdr.write(&buf[..max_write])?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Error<IO::Error>> {
+        Ok(self.state.borrow_mut().rdr.flush()?)
+    }
+}
+
+impl <'b, IO: ReadWriteSeek> Seek for FatSlice<'b, IO> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error<IO::Error>> {
+        let new_offset: i64 = match pos {
+            SeekFrom::Current(x) => self.offset as i64 + x,
+            SeekFrom::Start(x) => x as i64,
+            SeekFrom::End(x) => self.size as i64 + x,
+        };
+        if new_offset < 0 || new_offset as u64 > self.size {
+            return Err(Error::InvalidInput);
+        }
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
+
+/// Entry point into a FAT12/FAT16/FAT32 volume.
+///
+/// Owns the backing storage and the parsed BPB; `root_dir()` hands out the first `FatDir` handle
+/// that every other lookup is relative to.
+pub struct FatFileSystem<IO: ReadWriteSeek> {
+    state: RefCell<FatSharedState<IO>>,
+}
+
+impl <IO: ReadWriteSeek> FatFileSystem<IO> {
+    /// Parses the BIOS Parameter Block at the start of `storage` and returns a filesystem handle,
+    /// decoding short names as CP437 and stamping new/modified entries with the host's local time.
+    ///
+    /// Only available with the `std` feature, since it defaults to `LocalTimeProvider`, which
+    /// reads the host's clock - a `no_std` caller has no such clock and must go through
+    /// `new_with_options` with its own `TimeProvider` instead.
+    #[cfg(feature = "std")]
+    pub fn new<T: IntoStorage<IO>>(storage: T) -> Result<Self, Error<IO::Error>> {
+        Self::new_with_oem_cp(storage, Cp437OemCpConverter)
+    }
+
+    /// Like `new`, but decodes/encodes short names using `oem_cp_converter` instead of CP437.
+    #[cfg(feature = "std")]
+    pub fn new_with_oem_cp<T, OCC>(storage: T, oem_cp_converter: OCC) -> Result<Self, Error<IO::Error>>
+        where T: IntoStorage<IO>, OCC: OemCpConverter + 'static
+    {
+        Self::new_with_options(storage, oem_cp_converter, LocalTimeProvider)
+    }
+
+    /// Like `new`, but also lets the caller supply a `TimeProvider` instead of the host's local
+    /// clock - needed for deterministic snapshots and for any backend with no OS clock to read.
+    pub fn new_with_options<T, OCC, TP>(storage: T, oem_cp_converter: OCC, time_provider: TP) -> Result<Self, Error<IO::Error>>
+        where T: IntoStorage<IO>, OCC: OemCpConverter + 'static, TP: TimeProvider + 'static
+    {
+        let mut rdr = storage.into_storage();
+        rdr.seek(SeekFrom::Start(0))?;
+        // Read far enough to also cover the FAT32-only BPB_FATSz32 field (offset 36..40); earlier
+        // fields are shared by every FAT12/FAT16/FAT32 BPB.
+        let mut bpb = [0u8; 40];
+        rdr.read_exact(&mut bpb)?;
+        let bytes_per_sector = LittleEndian::read_u16(&bpb[11..13]);
+        let sectors_per_cluster = bpb[13];
+        let reserved_sectors = LittleEndian::read_u16(&bpb[14..16]);
+        let fats = bpb[16];
+        let root_entries = LittleEndian::read_u16(&bpb[17..19]);
+        let total_sectors_16 = LittleEndian::read_u16(&bpb[19..21]);
+        let sectors_per_fat_16 = LittleEndian::read_u16(&bpb[22..24]);
+        let total_sectors_32 = LittleEndian::read_u32(&bpb[32..36]);
+        let sectors_per_fat_32 = LittleEndian::read_u32(&bpb[36..40]);
+
+        if bytes_per_sector == 0 || sectors_per_cluster == 0 {
+            return Err(Error::CorruptedFileSystem);
+        }
+
+        let root_dir_sectors =
+            ((root_entries as u32 * 32) + (bytes_per_sector as u32 - 1)) / bytes_per_sector as u32;
+        // BPB_FATSz16/BPB_TotSec16 are 0 on a FAT32 volume, which instead stores these in the
+        // 32-bit fields added further into the BPB (BPB_FATSz32, BPB_TotSec32).
+        let sectors_per_fat = if sectors_per_fat_16 != 0 { sectors_per_fat_16 as u32 } else { sectors_per_fat_32 };
+        let root_dir_first_sector = reserved_sectors as u32 + fats as u32 * sectors_per_fat;
+        let first_data_sector = root_dir_first_sector + root_dir_sectors;
+        let total_sectors = if total_sectors_16 != 0 { total_sectors_16 as u32 } else { total_sectors_32 };
+        let data_sectors = total_sectors.saturating_sub(first_data_sector);
+        let total_clusters = data_sectors / sectors_per_cluster as u32;
+        let fat_type = FatType::from_cluster_count(total_clusters);
+
+        let state = FatSharedState {
+            rdr,
+            fat_type,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            fats,
+            sectors_per_fat,
+            root_dir_sectors,
+            root_dir_first_sector,
+            first_data_sector,
+            total_clusters,
+            oem_cp_converter: Box::new(oem_cp_converter),
+            time_provider: Box::new(time_provider),
+        };
+        Ok(FatFileSystem { state: RefCell::new(state) })
+    }
+
+    /// Returns the root directory of the volume.
+    ///
+    /// The root directory is always read from the fixed root-directory sectors that follow the
+    /// FATs. A FAT32 volume has none (its root directory lives in a cluster chain, whose first
+    /// cluster is not read from the BPB), so on FAT32 this returns an empty directory.
+    pub fn root_dir<'b>(&'b self) -> FatDir<'b, IO> {
+        let state = self.state.borrow();
+        let begin = state.offset_from_sector(state.root_dir_first_sector);
+        let size = state.root_dir_sectors as u64 * state.bytes_per_sector();
+        drop(state);
+        let slice = FatSlice::new(begin, size, &self.state);
+        FatDir::new(FatDirReader::Root(slice), &self.state)
+    }
+}
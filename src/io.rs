@@ -0,0 +1,199 @@
+//! A `no_std`-friendly storage abstraction, standing in for `std::io::{Read, Write, Seek}`.
+//!
+//! Every filesystem type in this crate is generic over a backend implementing
+//! [`ReadWriteSeek`] and reports errors as `Error<IO::Error>` rather than `std::io::Error`, so
+//! the crate can be mounted on a plain embedded block device with no `std` in scope. Callers
+//! that already have a `std::io` backend don't need to do anything differently: [`IntoStorage`]
+//! wraps it in [`StdIoWrapper`] automatically.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use error::IoError;
+
+/// Mirrors `std::io::SeekFrom` without depending on `std`.
+#[derive(Clone, Copy, Debug)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+#[cfg(feature = "std")]
+impl From<SeekFrom> for std::io::SeekFrom {
+    fn from(pos: SeekFrom) -> Self {
+        match pos {
+            SeekFrom::Start(x) => std::io::SeekFrom::Start(x),
+            SeekFrom::End(x) => std::io::SeekFrom::End(x),
+            SeekFrom::Current(x) => std::io::SeekFrom::Current(x),
+        }
+    }
+}
+
+/// Carries the error type shared by `Read`, `Write` and `Seek` for a given backend.
+pub trait IoBase {
+    type Error: IoError;
+}
+
+/// `no_std`-friendly analogue of `std::io::Read`.
+pub trait Read: IoBase {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let mut pos = 0;
+        while pos < buf.len() {
+            match self.read(&mut buf[pos..])? {
+                0 => return Err(Self::Error::new_unexpected_eof_error()),
+                n => pos += n,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `no_std`-friendly analogue of `std::io::Write`.
+pub trait Write: IoBase {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error>;
+    fn flush(&mut self) -> Result<(), Self::Error>;
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        let mut pos = 0;
+        while pos < buf.len() {
+            match self.write(&buf[pos..])? {
+                0 => return Err(Self::Error::new_write_zero_error()),
+                n => pos += n,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `no_std`-friendly analogue of `std::io::Seek`.
+pub trait Seek: IoBase {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error>;
+}
+
+/// A storage backend a FAT volume can be mounted on: readable, writable and seekable.
+pub trait ReadWriteSeek: Read + Write + Seek {}
+impl<T: Read + Write + Seek> ReadWriteSeek for T {}
+
+/// Little-endian integer reads built on top of `Read`, replacing `byteorder::ReadBytesExt` (which
+/// is only implemented for `std::io::Read`).
+pub(crate) trait ReadLeExt: Read {
+    fn read_u8(&mut self) -> Result<u8, Self::Error> {
+        let mut buf = [0u8; 1];
+        self.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_u16_le(&mut self) -> Result<u16, Self::Error> {
+        let mut buf = [0u8; 2];
+        self.read_exact(&mut buf)?;
+        Ok(LittleEndian::read_u16(&buf))
+    }
+
+    fn read_u32_le(&mut self) -> Result<u32, Self::Error> {
+        let mut buf = [0u8; 4];
+        self.read_exact(&mut buf)?;
+        Ok(LittleEndian::read_u32(&buf))
+    }
+
+    fn read_u16_into_le(&mut self, dst: &mut [u16]) -> Result<(), Self::Error> {
+        for slot in dst.iter_mut() {
+            *slot = self.read_u16_le()?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read> ReadLeExt for T {}
+
+/// Little-endian integer writes built on top of `Write`, replacing `byteorder::WriteBytesExt`.
+pub(crate) trait WriteLeExt: Write {
+    fn write_u8(&mut self, n: u8) -> Result<(), Self::Error> {
+        self.write_all(&[n])
+    }
+
+    fn write_u16_le(&mut self, n: u16) -> Result<(), Self::Error> {
+        let mut buf = [0u8; 2];
+        LittleEndian::write_u16(&mut buf, n);
+        self.write_all(&buf)
+    }
+
+    fn write_u32_le(&mut self, n: u32) -> Result<(), Self::Error> {
+        let mut buf = [0u8; 4];
+        LittleEndian::write_u32(&mut buf, n);
+        self.write_all(&buf)
+    }
+}
+
+impl<T: Write> WriteLeExt for T {}
+
+/// Converts a caller-supplied storage value into the concrete backend type a filesystem will
+/// use. The blanket `std` impl lets existing callers keep passing a `std::io` reader/writer
+/// unchanged; it gets wrapped in [`StdIoWrapper`] for them.
+pub trait IntoStorage<T: ReadWriteSeek> {
+    fn into_storage(self) -> T;
+}
+
+impl<T: ReadWriteSeek> IntoStorage<T> for T {
+    fn into_storage(self) -> T {
+        self
+    }
+}
+
+#[cfg(feature = "std")]
+mod std_io {
+    use std::io;
+
+    use super::{IoBase, Read, Write, Seek, SeekFrom, IntoStorage};
+
+    /// Adapts a `std::io::{Read, Write, Seek}` backend to this crate's storage traits.
+    pub struct StdIoWrapper<T> {
+        inner: T,
+    }
+
+    impl<T> StdIoWrapper<T> {
+        pub fn new(inner: T) -> Self {
+            StdIoWrapper { inner }
+        }
+
+        pub fn into_inner(self) -> T {
+            self.inner
+        }
+    }
+
+    impl<T> IoBase for StdIoWrapper<T> {
+        type Error = io::Error;
+    }
+
+    impl<T: io::Read> Read for StdIoWrapper<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl<T: io::Write> Write for StdIoWrapper<T> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl<T: io::Seek> Seek for StdIoWrapper<T> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos.into())
+        }
+    }
+
+    impl<T: io::Read + io::Write + io::Seek> IntoStorage<StdIoWrapper<T>> for T {
+        fn into_storage(self) -> StdIoWrapper<T> {
+            StdIoWrapper::new(self)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub use self::std_io::StdIoWrapper;
@@ -0,0 +1,45 @@
+//! A library for reading and writing FAT12/FAT16/FAT32 filesystems.
+//!
+//! This is a fork of the `fatfs` crate adapted for use inside MartyPC, an x86 PC emulator,
+//! where it is used to inspect and modify the contents of floppy and hard disk images.
+//!
+//! Builds `no_std` by default (plus `alloc`, for the `Vec`/`String`/`Box` a directory listing and
+//! short-name handling need) so the crate can be mounted on a plain embedded block device. Enable
+//! the `std` feature to get `StdIoWrapper` (an `IntoStorage` impl for any `std::io` backend),
+//! `std::error::Error`/`std::io::Error` conversions for `Error`, and `LocalTimeProvider`.
+
+#![crate_name = "fatfs"]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+#[macro_use]
+extern crate bitflags;
+extern crate byteorder;
+extern crate chrono;
+
+mod dir;
+mod error;
+mod file;
+mod fs;
+mod io;
+mod oem_cp;
+mod partition;
+mod table;
+mod time;
+
+pub use dir::{FatDir, FatDirEntry, FatFileAttributes};
+pub use error::{Error, IoError};
+pub use file::FatFile;
+pub use fs::{FatFileSystem, FatSlice};
+pub use io::{IntoStorage, IoBase, Read, ReadWriteSeek, Seek, SeekFrom, Write};
+#[cfg(feature = "std")]
+pub use io::StdIoWrapper;
+pub use oem_cp::{Cp437OemCpConverter, OemCpConverter};
+pub use partition::{MbrPartition, MbrPartitionTable, PartitionSlice};
+pub use table::FatType;
+#[cfg(feature = "std")]
+pub use time::LocalTimeProvider;
+pub use time::{RawDateTime, TimeProvider};
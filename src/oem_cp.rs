@@ -0,0 +1,46 @@
+//! Decoding/encoding of 8.3 short names between their on-disk OEM code page and Unicode.
+//!
+//! DOS stores short names in whatever code page was active when the disk was formatted, not
+//! UTF-8, so bytes >= 0x80 cannot be interpreted with `str::from_utf8`. `OemCpConverter` lets the
+//! code page be swapped out; `Cp437OemCpConverter` reproduces the original IBM PC default and is
+//! used unless the caller picks something else.
+
+/// Translates bytes of an 8.3 short name to and from a single-byte OEM code page.
+pub trait OemCpConverter {
+    /// Decodes a single code-page byte into its Unicode character.
+    fn decode(&self, oem_char: u8) -> char;
+    /// Encodes a Unicode character back into a code-page byte, if representable.
+    fn encode(&self, uni_char: char) -> Option<u8>;
+}
+
+// CP437 characters for bytes 0x80-0xFF, in order. Bytes below 0x80 are plain ASCII.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{00A0}',
+];
+
+/// The original IBM PC code page (CP437), used unless the caller supplies another converter.
+pub struct Cp437OemCpConverter;
+
+impl OemCpConverter for Cp437OemCpConverter {
+    fn decode(&self, oem_char: u8) -> char {
+        if oem_char < 0x80 {
+            oem_char as char
+        } else {
+            CP437_HIGH[(oem_char - 0x80) as usize]
+        }
+    }
+
+    fn encode(&self, uni_char: char) -> Option<u8> {
+        if (uni_char as u32) < 0x80 {
+            return Some(uni_char as u8);
+        }
+        CP437_HIGH.iter().position(|&c| c == uni_char).map(|i| 0x80 + i as u8)
+    }
+}
@@ -0,0 +1,163 @@
+//! MBR partition table parsing.
+//!
+//! Disk images (as opposed to bare floppy images) typically start with a classic MBR: a
+//! 512-byte boot sector holding a 4-entry partition table and a 0x55AA signature. This lets a
+//! caller read that table, pick a partition, and mount it as a `FatFileSystem` through a bounded
+//! `FatSlice`-style view over the underlying reader, so every offset `FatDir`/`FatFile` compute is
+//! transparently relative to the partition's starting LBA rather than the start of the disk.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use error::{Error, IoError};
+use fs::FatFileSystem;
+use io::{IntoStorage, IoBase, Read, ReadWriteSeek, Seek, SeekFrom, Write};
+
+const PARTITION_TABLE_OFFSET: usize = 446;
+const PARTITION_ENTRY_LEN: usize = 16;
+const NUM_PARTITIONS: usize = 4;
+const SECTOR_SIZE: u64 = 512;
+
+/// One 16-byte entry of the MBR partition table.
+#[derive(Clone, Copy, Debug)]
+pub struct MbrPartition {
+    pub bootable: bool,
+    pub partition_type: u8,
+    pub lba_start: u32,
+    pub sector_count: u32,
+}
+
+impl MbrPartition {
+    /// Whether `partition_type` is one of the common FAT12/FAT16/FAT32 type bytes
+    /// (0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E).
+    pub fn is_fat(&self) -> bool {
+        match self.partition_type {
+            0x01 | 0x04 | 0x06 | 0x0B | 0x0C | 0x0E => true,
+            _ => false,
+        }
+    }
+}
+
+/// The 4-entry partition table parsed out of an MBR.
+pub struct MbrPartitionTable {
+    partitions: [Option<MbrPartition>; NUM_PARTITIONS],
+}
+
+impl MbrPartitionTable {
+    /// Reads the MBR from the start of `storage`, returning the parsed table together with the
+    /// storage itself so a chosen partition can be mounted with `FatFileSystem::from_partition`.
+    ///
+    /// Fails with `Error::CorruptedFileSystem` if the 0x55AA signature at offset 510 is missing.
+    pub fn read<T, IO>(storage: T) -> Result<(Self, IO), Error<IO::Error>>
+        where T: IntoStorage<IO>, IO: ReadWriteSeek
+    {
+        let mut rdr = storage.into_storage();
+        rdr.seek(SeekFrom::Start(0))?;
+        let mut sector = [0u8; 512];
+        rdr.read_exact(&mut sector)?;
+        if sector[510] != 0x55 || sector[511] != 0xAA {
+            return Err(Error::CorruptedFileSystem);
+        }
+
+        let mut partitions = [None; NUM_PARTITIONS];
+        for i in 0..NUM_PARTITIONS {
+            let start = PARTITION_TABLE_OFFSET + i * PARTITION_ENTRY_LEN;
+            let entry = &sector[start..start + PARTITION_ENTRY_LEN];
+            let partition_type = entry[4];
+            if partition_type != 0 {
+                partitions[i] = Some(MbrPartition {
+                    bootable: entry[0] == 0x80,
+                    partition_type,
+                    lba_start: LittleEndian::read_u32(&entry[8..12]),
+                    sector_count: LittleEndian::read_u32(&entry[12..16]),
+                });
+            }
+        }
+        Ok((MbrPartitionTable { partitions }, rdr))
+    }
+
+    /// The table's four partition slots, in on-disk order; `None` marks an unused slot.
+    pub fn partitions(&self) -> &[Option<MbrPartition>; NUM_PARTITIONS] {
+        &self.partitions
+    }
+}
+
+/// A bounded, seekable view over one partition's sectors on the underlying storage, so a
+/// `FatFileSystem` mounted on it sees offset 0 as the start of the partition.
+pub struct PartitionSlice<IO> {
+    inner: IO,
+    begin: u64,
+    size: u64,
+    offset: u64,
+}
+
+impl <IO: ReadWriteSeek> PartitionSlice<IO> {
+    fn new(inner: IO, begin: u64, size: u64) -> Self {
+        PartitionSlice { inner, begin, size, offset: 0 }
+    }
+}
+
+impl <IO: ReadWriteSeek> IoBase for PartitionSlice<IO> {
+    type Error = IO::Error;
+}
+
+impl <IO: ReadWriteSeek> Read for PartitionSlice<IO> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IO::Error> {
+        let remaining = self.size.saturating_sub(self.offset);
+        let max_read = core::cmp::min(buf.len() as u64, remaining) as usize;
+        if max_read == 0 {
+            return Ok(0);
+        }
+        self.inner.seek(SeekFrom::Start(self.begin + self.offset))?;
+        let n = self.inner.read(&mut buf[..max_read])?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+impl <IO: ReadWriteSeek> Write for PartitionSlice<IO> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IO::Error> {
+        let remaining = self.size.saturating_sub(self.offset);
+        let max_write = core::cmp::min(buf.len() as u64, remaining) as usize;
+        if max_write == 0 {
+            return Ok(0);
+        }
+        self.inner.seek(SeekFrom::Start(self.begin + self.offset))?;
+        let n = self.inner.write(&buf[..max_write])?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), IO::Error> {
+        self.inner.flush()
+    }
+}
+
+impl <IO: ReadWriteSeek> Seek for PartitionSlice<IO> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, IO::Error> {
+        let new_offset: i64 = match pos {
+            SeekFrom::Current(x) => self.offset as i64 + x,
+            SeekFrom::Start(x) => x as i64,
+            SeekFrom::End(x) => self.size as i64 + x,
+        };
+        if new_offset < 0 || new_offset as u64 > self.size {
+            return Err(IO::Error::new_unexpected_eof_error());
+        }
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
+
+impl <IO: ReadWriteSeek> FatFileSystem<PartitionSlice<IO>> {
+    /// Mounts `partition` (as returned by `MbrPartitionTable::partitions`) as a FAT volume,
+    /// translating every offset `FatDir`/`FatFile` compute to be relative to the partition's
+    /// starting LBA rather than the start of `storage`.
+    ///
+    /// Only available with the `std` feature: it goes through `FatFileSystem::new`, which defaults
+    /// to the host-clock-backed `LocalTimeProvider`.
+    #[cfg(feature = "std")]
+    pub fn from_partition(storage: IO, partition: &MbrPartition) -> Result<Self, Error<IO::Error>> {
+        let begin = partition.lba_start as u64 * SECTOR_SIZE;
+        let size = partition.sector_count as u64 * SECTOR_SIZE;
+        FatFileSystem::new(PartitionSlice::new(storage, begin, size))
+    }
+}
@@ -0,0 +1,153 @@
+use alloc::vec;
+
+use error::Error;
+use fs::FatSharedStateRef;
+use io::{ReadLeExt, ReadWriteSeek, Seek, SeekFrom, Write, WriteLeExt};
+
+/// Which on-disk FAT table format a volume uses. Determined from the total cluster count, per
+/// the Microsoft FAT specification (clusters < 4085 is FAT12, < 65525 is FAT16, else FAT32).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    pub(crate) fn from_cluster_count(total_clusters: u32) -> FatType {
+        if total_clusters < 4085 {
+            FatType::Fat12
+        } else if total_clusters < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        }
+    }
+}
+
+pub(crate) const FREE_CLUSTER: u32 = 0;
+pub(crate) const EOF_CLUSTER: u32 = 0x0FFF_FFFF;
+
+fn is_eof(fat_type: FatType, value: u32) -> bool {
+    match fat_type {
+        FatType::Fat12 => value >= 0x0FF8,
+        FatType::Fat16 => value >= 0xFFF8,
+        FatType::Fat32 => value >= 0x0FFF_FFF8,
+    }
+}
+
+/// Reads the FAT entry for `cluster` out of the first FAT table.
+pub(crate) fn get_fat_entry<'b, IO: ReadWriteSeek>(state: FatSharedStateRef<'b, IO>, cluster: u32) -> Result<u32, Error<IO::Error>> {
+    let mut state = state.borrow_mut();
+    let fat_type = state.fat_type;
+    match fat_type {
+        FatType::Fat12 => {
+            let fat_byte_offset = cluster as u64 + cluster as u64 / 2;
+            let offset = state.fat_offset(0) + fat_byte_offset;
+            state.rdr.seek(SeekFrom::Start(offset))?;
+            let packed = state.rdr.read_u16_le()?;
+            let value = if cluster & 1 == 0 { packed & 0x0FFF } else { packed >> 4 };
+            Ok(value as u32)
+        },
+        FatType::Fat16 => {
+            let offset = state.fat_offset(0) + cluster as u64 * 2;
+            state.rdr.seek(SeekFrom::Start(offset))?;
+            Ok(state.rdr.read_u16_le()? as u32)
+        },
+        FatType::Fat32 => {
+            let offset = state.fat_offset(0) + cluster as u64 * 4;
+            state.rdr.seek(SeekFrom::Start(offset))?;
+            Ok(state.rdr.read_u32_le()? & 0x0FFF_FFFF)
+        },
+    }
+}
+
+/// Writes `value` into the FAT entry for `cluster`, mirroring the write across every FAT copy.
+pub(crate) fn set_fat_entry<'b, IO: ReadWriteSeek>(state: FatSharedStateRef<'b, IO>, cluster: u32, value: u32) -> Result<(), Error<IO::Error>> {
+    let mut state = state.borrow_mut();
+    let fat_type = state.fat_type;
+    let fats = state.fats;
+    for fat_index in 0..fats {
+        match fat_type {
+            FatType::Fat12 => {
+                let fat_byte_offset = cluster as u64 + cluster as u64 / 2;
+                let offset = state.fat_offset(fat_index) + fat_byte_offset;
+                state.rdr.seek(SeekFrom::Start(offset))?;
+                let old_packed = state.rdr.read_u16_le()?;
+                let new_packed = if cluster & 1 == 0 {
+                    (old_packed & 0xF000) | (value as u16 & 0x0FFF)
+                } else {
+                    (old_packed & 0x000F) | ((value as u16 & 0x0FFF) << 4)
+                };
+                state.rdr.seek(SeekFrom::Start(offset))?;
+                state.rdr.write_u16_le(new_packed)?;
+            },
+            FatType::Fat16 => {
+                let offset = state.fat_offset(fat_index) + cluster as u64 * 2;
+                state.rdr.seek(SeekFrom::Start(offset))?;
+                state.rdr.write_u16_le(value as u16)?;
+            },
+            FatType::Fat32 => {
+                let offset = state.fat_offset(fat_index) + cluster as u64 * 4;
+                state.rdr.seek(SeekFrom::Start(offset))?;
+                state.rdr.write_u32_le(value & 0x0FFF_FFFF)?;
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Returns the next cluster in the chain, or `None` if `cluster` is the last one.
+pub(crate) fn get_next_cluster<'b, IO: ReadWriteSeek>(state: FatSharedStateRef<'b, IO>, cluster: u32) -> Result<Option<u32>, Error<IO::Error>> {
+    let fat_type = state.borrow().fat_type;
+    let value = get_fat_entry(state, cluster)?;
+    if is_eof(fat_type, value) || value == FREE_CLUSTER {
+        Ok(None)
+    } else {
+        Ok(Some(value))
+    }
+}
+
+/// Scans the FAT for a free cluster, marks it as the end of a new chain, and returns its number.
+///
+/// The cluster's data is zeroed before it's handed back, since it may hold whatever a previous
+/// (freed) occupant left there: a directory relies on unused slots reading back as `0x00`
+/// (never-used) rather than storage garbage, and zeroing on every allocation is simplest.
+pub(crate) fn alloc_cluster<'b, IO: ReadWriteSeek>(state: FatSharedStateRef<'b, IO>, prev_cluster: Option<u32>) -> Result<u32, Error<IO::Error>> {
+    let total_clusters = state.borrow().total_clusters;
+    let mut cluster = 2u32;
+    let new_cluster = loop {
+        if cluster >= total_clusters + 2 {
+            return Err(Error::NotEnoughSpace);
+        }
+        if get_fat_entry(state, cluster)? == FREE_CLUSTER {
+            break cluster;
+        }
+        cluster += 1;
+    };
+    set_fat_entry(state, new_cluster, EOF_CLUSTER)?;
+    if let Some(prev) = prev_cluster {
+        set_fat_entry(state, prev, new_cluster)?;
+    }
+    zero_cluster(state, new_cluster)?;
+    Ok(new_cluster)
+}
+
+fn zero_cluster<'b, IO: ReadWriteSeek>(state: FatSharedStateRef<'b, IO>, cluster: u32) -> Result<(), Error<IO::Error>> {
+    let mut state = state.borrow_mut();
+    let offset = state.offset_from_cluster(cluster);
+    let zeros = vec![0u8; state.bytes_per_cluster() as usize];
+    state.rdr.seek(SeekFrom::Start(offset))?;
+    state.rdr.write_all(&zeros)?;
+    Ok(())
+}
+
+/// Frees every cluster in the chain starting at `cluster`.
+pub(crate) fn free_cluster_chain<'b, IO: ReadWriteSeek>(state: FatSharedStateRef<'b, IO>, cluster: u32) -> Result<(), Error<IO::Error>> {
+    let mut current = Some(cluster);
+    while let Some(c) = current {
+        current = get_next_cluster(state, c)?;
+        set_fat_entry(state, c, FREE_CLUSTER)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+//! Pluggable wall-clock access.
+//!
+//! FAT's on-disk date/time fields carry no timezone, so turning them into a `chrono::DateTime`
+//! requires picking one, and creating/modifying an entry requires a clock to stamp it with.
+//! `TimeProvider` supplies both without hardcoding the host's local timezone (or assuming an OS
+//! clock is available at all, as a no_std backend would need to).
+
+use chrono::FixedOffset;
+
+/// The components of a FAT date/time field, decoded with no timezone attached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawDateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub min: u8,
+    pub sec: u8,
+}
+
+/// Supplies the current time (to stamp newly created/modified entries) and the UTC offset that
+/// FAT's timezone-less date/time fields should be interpreted as.
+pub trait TimeProvider {
+    /// The current time, in the timezone `utc_offset` describes.
+    fn get_current_date_time(&self) -> RawDateTime;
+    /// The UTC offset used when turning a decoded `RawDateTime` into a `chrono::DateTime`.
+    fn utc_offset(&self) -> FixedOffset;
+}
+
+/// Reproduces the crate's original behavior: wall-clock time in the host's local timezone.
+///
+/// Needs the `std` feature - it reads the host's OS clock (via `chrono::Local`), which a `no_std`
+/// target has no way to provide. `no_std` callers implement `TimeProvider` themselves instead.
+#[cfg(feature = "std")]
+pub struct LocalTimeProvider;
+
+#[cfg(feature = "std")]
+impl TimeProvider for LocalTimeProvider {
+    fn get_current_date_time(&self) -> RawDateTime {
+        use chrono::{Datelike, Timelike};
+        let now = chrono::Local::now();
+        RawDateTime {
+            year: now.year() as u16,
+            month: now.month() as u8,
+            day: now.day() as u8,
+            hour: now.hour() as u8,
+            min: now.minute() as u8,
+            sec: now.second() as u8,
+        }
+    }
+
+    fn utc_offset(&self) -> FixedOffset {
+        *chrono::Local::now().offset()
+    }
+}